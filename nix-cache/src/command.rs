@@ -0,0 +1,425 @@
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output, Stdio};
+
+use sqlx::SqlitePool;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+use crate::db;
+use crate::gc_roots::GcRoot;
+use crate::nix_internal_log::NixInternalLog;
+use crate::op::Op;
+use crate::store_refs;
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("failed to spawn command: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("failed to read command output: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cache database error: {0}")]
+    Db(#[from] sqlx::Error),
+    #[error("failed to register a Nix GC root: {0}")]
+    GcSocket(#[source] std::io::Error),
+}
+
+/// Options controlling how a [`CachedCommand`] computes its cache key and
+/// whether it consults/populates the cache at all.
+#[derive(Debug, Clone)]
+pub struct CommandOptions {
+    /// Whether to cache the command's output, keyed on its argv and the
+    /// content hashes of the files it touches.
+    pub cache: bool,
+    /// Names of environment variables whose current value is folded into the
+    /// cache key. Nix evaluation is sensitive to impurities like `NIX_PATH`
+    /// and `NIX_CONFIG` that never show up in argv, so without this a
+    /// changed value would silently serve output evaluated under a
+    /// different environment.
+    pub impure_env: Vec<String>,
+    /// Whether to additionally resolve `builtins.currentSystem` and fold it
+    /// into the cache key. This captures `eval-system`/`system` settings from
+    /// the Nix configuration (including `NIX_CONFIG`) that `impure_env`
+    /// wouldn't see directly. The probe is a separate `nix eval` run without
+    /// this command's arguments; an `--eval-system` flag passed to the
+    /// command itself reaches the cache key only through argv.
+    pub capture_eval_system: bool,
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        Self {
+            cache: true,
+            impure_env: vec!["NIX_PATH".to_string(), "NIX_CONFIG".to_string()],
+            capture_eval_system: false,
+        }
+    }
+}
+
+/// A [`Command`] whose output is memoized in the cache database.
+///
+/// When caching is enabled, the command is re-run under
+/// `--log-format internal-json` so its stderr can be parsed into [`Op`]s; the
+/// paths those `Op`s reference are recorded as the entry's dependencies,
+/// hashed when they're ordinary files or checked for mere existence when
+/// they're content-addressed store paths (see
+/// `Op::dependency_is_content_addressed`). Paths other than store paths that
+/// can't be read as files (directories, or files already gone) are not
+/// recorded. A cached entry is only served back if every recorded dependency
+/// still passes its check.
+pub struct CachedCommand<'a> {
+    pool: &'a SqlitePool,
+    command: Command,
+    options: CommandOptions,
+    /// Held open for the lifetime of this `CachedCommand` so the store paths
+    /// its output references aren't collected out from under the cache.
+    gc_roots: Vec<GcRoot>,
+}
+
+impl<'a> CachedCommand<'a> {
+    pub fn new(pool: &'a SqlitePool, command: Command, options: CommandOptions) -> Self {
+        Self {
+            pool,
+            command,
+            options,
+            gc_roots: Vec::new(),
+        }
+    }
+
+    /// A stable key for this command's argv and the impurities configured in
+    /// `self.options`, used to look up cached output.
+    async fn cache_key(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        let std_command = self.command.as_std();
+        hasher.update(std_command.get_program().as_encoded_bytes());
+        for arg in std_command.get_args() {
+            hasher.update(b"\0");
+            hasher.update(arg.as_encoded_bytes());
+        }
+
+        for name in &self.options.impure_env {
+            hasher.update(b"\0");
+            hasher.update(name.as_bytes());
+            hasher.update(b"=");
+            if let Some(value) = std::env::var_os(name) {
+                hasher.update(value.as_encoded_bytes());
+            }
+        }
+
+        if self.options.capture_eval_system {
+            let system = tokio::task::spawn_blocking(resolve_eval_system)
+                .await
+                .expect("resolve_eval_system panicked")
+                .unwrap_or_default();
+            hasher.update(b"\0eval-system=");
+            hasher.update(system.as_bytes());
+        }
+
+        hasher.finalize().to_hex().to_string()
+    }
+
+    pub async fn run(&mut self) -> Result<Output, CommandError> {
+        if self.options.cache {
+            let key = self.cache_key().await;
+            if let Some(output) = self.lookup_valid_entry(&key).await? {
+                return Ok(output);
+            }
+            self.command.arg("--log-format").arg("internal-json");
+            return self.run_and_cache(&key).await;
+        }
+
+        self.command.output().await.map_err(CommandError::Spawn)
+    }
+
+    /// Looks up `key` in the cache and returns its output only if every
+    /// recorded dependency still passes its validity check (see
+    /// [`db::Dependency::hash`]).
+    async fn lookup_valid_entry(&mut self, key: &str) -> Result<Option<Output>, CommandError> {
+        let Some(entry) = db::get_cache_entry(self.pool, key).await? else {
+            return Ok(None);
+        };
+
+        let dependencies = db::get_dependencies(self.pool, entry.id).await?;
+        for dep in &dependencies {
+            let path = Path::new(&dep.path);
+            let still_valid = match &dep.hash {
+                Some(expected_hash) => hash_file(path).as_deref() == Some(expected_hash.as_str()),
+                None => path.exists(),
+            };
+            if !still_valid {
+                if let Some(project_boundary) = &dep.project_boundary {
+                    db::delete_cache_entries_by_project_boundary(self.pool, project_boundary)
+                        .await?;
+                }
+                return Ok(None);
+            }
+        }
+
+        let store_paths = db::get_store_references(self.pool, entry.id).await?;
+        self.register_gc_roots(&store_paths).await?;
+
+        Ok(Some(Output {
+            status: exit_status_from_code(entry.status_code),
+            stdout: entry.stdout,
+            stderr: entry.stderr,
+        }))
+    }
+
+    /// Registers each of `store_paths` as a temporary GC root, holding the
+    /// registering connections open in `self.gc_roots`.
+    ///
+    /// Connecting can block for up to a couple of seconds retrying through a
+    /// GC-collector restart, so each registration is offloaded via
+    /// `spawn_blocking` rather than stalling the async executor thread.
+    async fn register_gc_roots(&mut self, store_paths: &[String]) -> Result<(), CommandError> {
+        for store_path in store_paths {
+            let store_path = PathBuf::from(store_path);
+            let gc_root = tokio::task::spawn_blocking(move || GcRoot::register(&store_path))
+                .await
+                .expect("GcRoot::register panicked")?;
+            self.gc_roots.push(gc_root);
+        }
+        Ok(())
+    }
+
+    /// Runs the command, parses its stderr for dependency `Op`s, stores the
+    /// output and dependency hashes in the cache, and returns the output.
+    async fn run_and_cache(&mut self, key: &str) -> Result<Output, CommandError> {
+        let mut child = self
+            .command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(CommandError::Spawn)?;
+
+        let mut stdout_pipe = child.stdout.take().expect("stdout was piped");
+        let mut stderr_pipe = child.stderr.take().expect("stderr was piped");
+
+        let mut stdout_buf = Vec::new();
+        let mut stderr_buf = String::new();
+
+        // Drain stdout and stderr concurrently rather than one after the
+        // other: if the child fills the stdout pipe buffer while it's still
+        // writing to stderr (common for `--log-format internal-json`, which
+        // can produce plenty of both), reading stderr to completion first
+        // would deadlock waiting on a stdout pipe nobody's draining.
+        let (stdout_result, stderr_result, status) = tokio::join!(
+            stdout_pipe.read_to_end(&mut stdout_buf),
+            stderr_pipe.read_to_string(&mut stderr_buf),
+            child.wait()
+        );
+        stdout_result?;
+        stderr_result?;
+        let status = status?;
+
+        let ops: Vec<Op> = stderr_buf
+            .lines()
+            .filter_map(NixInternalLog::parse_line)
+            .filter_map(|log| Op::from_internal_log(&log))
+            .collect();
+
+        let output = Output {
+            status,
+            stdout: stdout_buf,
+            stderr: stderr_buf.into_bytes(),
+        };
+
+        let entry_id = db::insert_cache_entry(
+            self.pool,
+            key,
+            &output.stdout,
+            &output.stderr,
+            exit_code(&output.status),
+        )
+        .await?;
+
+        for op in &ops {
+            let Some(path) = op.dependency_path() else {
+                continue;
+            };
+            let hash = if op.dependency_is_content_addressed() {
+                None
+            } else {
+                let Some(hash) = hash_file(path) else {
+                    continue;
+                };
+                Some(hash)
+            };
+            let project_boundary = op.project_boundary();
+            let project_boundary = project_boundary.as_ref().map(|p| p.to_string_lossy());
+            db::insert_dependency(
+                self.pool,
+                entry_id,
+                &path.to_string_lossy(),
+                hash.as_deref(),
+                project_boundary.as_deref(),
+            )
+            .await?;
+        }
+
+        let mut referenced_store_paths = store_refs::scan_store_paths(&output.stdout);
+        referenced_store_paths.extend(store_refs::scan_store_paths(&output.stderr));
+        for store_path in &referenced_store_paths {
+            db::insert_store_reference(self.pool, entry_id, store_path).await?;
+        }
+
+        let referenced_store_paths: Vec<String> = referenced_store_paths.into_iter().collect();
+        self.register_gc_roots(&referenced_store_paths).await?;
+
+        Ok(output)
+    }
+}
+
+/// Hashes a file's contents with blake3, returning `None` if it can't be read.
+fn hash_file(path: &Path) -> Option<String> {
+    let contents = std::fs::read(path).ok()?;
+    Some(blake3::hash(&contents).to_hex().to_string())
+}
+
+/// Resolves `builtins.currentSystem` with a standalone `nix eval`, reflecting
+/// `eval-system`/`system` settings from the Nix configuration and
+/// `NIX_CONFIG`.
+fn resolve_eval_system() -> Option<String> {
+    let output = std::process::Command::new("nix")
+        .args(["eval", "--raw", "--expr", "builtins.currentSystem"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn exit_code(status: &ExitStatus) -> i32 {
+    status.code().unwrap_or(-1)
+}
+
+#[cfg(unix)]
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(not(unix))]
+fn exit_status_from_code(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sets up a fresh cache database backed by a temp file rather than
+    /// `sqlite::memory:`: the pool can open more than one connection, and
+    /// each connection to a `:memory:` URL gets its own separate database.
+    async fn test_pool() -> (tempfile::TempDir, SqlitePool) {
+        let dir = tempfile::tempdir().expect("create tempdir");
+        let db_path = dir.path().join("cache.db");
+        let pool = db::setup_db(&format!("sqlite://{}?mode=rwc", db_path.display()))
+            .await
+            .expect("set up db");
+        (dir, pool)
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_replays_output() {
+        let (_dir, pool) = test_pool().await;
+        let mut cmd = CachedCommand::new(
+            &pool,
+            Command::new("echo").arg("hello"),
+            CommandOptions::default(),
+        );
+        let key = cmd.cache_key().await;
+        cmd.command.arg("--log-format").arg("internal-json");
+        let first = cmd.run_and_cache(&key).await.expect("run_and_cache");
+
+        let hit = cmd
+            .lookup_valid_entry(&key)
+            .await
+            .expect("lookup")
+            .expect("cache hit");
+        assert_eq!(hit.stdout, first.stdout);
+        assert_eq!(hit.stderr, first.stderr);
+    }
+
+    #[tokio::test]
+    async fn test_dependency_hash_mismatch_coarsely_deletes_project_siblings() {
+        let (dir, pool) = test_pool().await;
+        let project_dir = dir.path();
+        std::fs::write(project_dir.join("flake.nix"), "").expect("write flake.nix");
+        let dep_file = project_dir.join("default.nix");
+        std::fs::write(&dep_file, "original").expect("write dep file");
+        let project_boundary = project_dir.to_string_lossy().to_string();
+        let original_hash = blake3::hash(b"original").to_hex().to_string();
+
+        let entry_a = db::insert_cache_entry(&pool, "key-a", b"out-a", b"", 0)
+            .await
+            .expect("insert entry a");
+        let entry_b = db::insert_cache_entry(&pool, "key-b", b"out-b", b"", 0)
+            .await
+            .expect("insert entry b");
+        db::insert_dependency(
+            &pool,
+            entry_a,
+            &dep_file.to_string_lossy(),
+            Some(&original_hash),
+            Some(&project_boundary),
+        )
+        .await
+        .expect("insert dep a");
+        db::insert_dependency(
+            &pool,
+            entry_b,
+            &dep_file.to_string_lossy(),
+            Some(&original_hash),
+            Some(&project_boundary),
+        )
+        .await
+        .expect("insert dep b");
+
+        std::fs::write(&dep_file, "changed").expect("change dep file");
+
+        let mut cmd = CachedCommand::new(&pool, Command::new("echo"), CommandOptions::default());
+        let result = cmd.lookup_valid_entry("key-a").await.expect("lookup");
+        assert!(result.is_none());
+
+        assert!(db::get_cache_entry(&pool, "key-a")
+            .await
+            .expect("get entry a")
+            .is_none());
+        assert!(db::get_cache_entry(&pool, "key-b")
+            .await
+            .expect("get entry b")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_changes_with_impure_env_value() {
+        let (_dir, pool) = test_pool().await;
+        let options = CommandOptions {
+            impure_env: vec!["NIX_CACHE_TEST_IMPURE_VAR".to_string()],
+            ..CommandOptions::default()
+        };
+
+        unsafe {
+            std::env::set_var("NIX_CACHE_TEST_IMPURE_VAR", "one");
+        }
+        let key_one = CachedCommand::new(&pool, Command::new("echo"), options.clone())
+            .cache_key()
+            .await;
+
+        unsafe {
+            std::env::set_var("NIX_CACHE_TEST_IMPURE_VAR", "two");
+        }
+        let key_two = CachedCommand::new(&pool, Command::new("echo"), options.clone())
+            .cache_key()
+            .await;
+
+        unsafe {
+            std::env::remove_var("NIX_CACHE_TEST_IMPURE_VAR");
+        }
+        assert_ne!(key_one, key_two);
+    }
+}
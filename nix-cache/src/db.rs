@@ -0,0 +1,228 @@
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use sqlx::Row;
+
+/// A cached command result, keyed by `cache_key`.
+pub(crate) struct CacheEntry {
+    pub id: i64,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status_code: i32,
+}
+
+/// Connects to the cache database at `database_url`, creating it and its
+/// schema if they don't already exist.
+pub async fn setup_db(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect(database_url)
+        .await?;
+
+    // Required for `ON DELETE CASCADE` (used to evict a cache entry's
+    // dependencies and store references) to actually take effect; SQLite
+    // ignores foreign keys by default.
+    sqlx::query("PRAGMA foreign_keys = ON")
+        .execute(&pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS cache_entries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            cache_key TEXT NOT NULL UNIQUE,
+            stdout BLOB NOT NULL,
+            stderr BLOB NOT NULL,
+            status_code INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS cache_dependencies (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            cache_entry_id INTEGER NOT NULL REFERENCES cache_entries(id) ON DELETE CASCADE,
+            path TEXT NOT NULL,
+            hash TEXT,
+            project_boundary TEXT
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS store_path_references (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            cache_entry_id INTEGER NOT NULL REFERENCES cache_entries(id) ON DELETE CASCADE,
+            store_path TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+
+    Ok(pool)
+}
+
+/// Looks up a cache entry by its key.
+pub(crate) async fn get_cache_entry(
+    pool: &SqlitePool,
+    cache_key: &str,
+) -> Result<Option<CacheEntry>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT id, stdout, stderr, status_code FROM cache_entries WHERE cache_key = ?",
+    )
+    .bind(cache_key)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| CacheEntry {
+        id: row.get("id"),
+        stdout: row.get("stdout"),
+        stderr: row.get("stderr"),
+        status_code: row.get("status_code"),
+    }))
+}
+
+/// Inserts a new cache entry, replacing any existing entry with the same key.
+pub(crate) async fn insert_cache_entry(
+    pool: &SqlitePool,
+    cache_key: &str,
+    stdout: &[u8],
+    stderr: &[u8],
+    status_code: i32,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query("DELETE FROM cache_entries WHERE cache_key = ?")
+        .bind(cache_key)
+        .execute(pool)
+        .await?;
+
+    let result = sqlx::query(
+        "INSERT INTO cache_entries (cache_key, stdout, stderr, status_code) VALUES (?, ?, ?, ?)",
+    )
+    .bind(cache_key)
+    .bind(stdout)
+    .bind(stderr)
+    .bind(status_code)
+    .execute(pool)
+    .await?;
+
+    Ok(result.last_insert_rowid())
+}
+
+/// Records that `cache_entry_id` depends on `path`. `hash` is the blake3
+/// hash of `path`'s contents at the time the entry was created, for
+/// dependencies that should be invalidated by re-hashing; it's `None` for
+/// content-addressed store paths (see `Op::dependency_is_content_addressed`),
+/// which are instead invalidated if `path` stops existing. `project_boundary`,
+/// if known, scopes the dependency to the enclosing project directory.
+pub(crate) async fn insert_dependency(
+    pool: &SqlitePool,
+    cache_entry_id: i64,
+    path: &str,
+    hash: Option<&str>,
+    project_boundary: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO cache_dependencies (cache_entry_id, path, hash, project_boundary) VALUES (?, ?, ?, ?)",
+    )
+    .bind(cache_entry_id)
+    .bind(path)
+    .bind(hash)
+    .bind(project_boundary)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// A recorded dependency of a cache entry.
+pub(crate) struct Dependency {
+    pub path: String,
+    /// `None` for content-addressed store paths, which are checked by
+    /// existence rather than by re-hashing; see `insert_dependency`.
+    pub hash: Option<String>,
+    /// The project directory `path` was resolved under, if any. Shared by
+    /// every dependency (across every cache entry) under the same project,
+    /// so it can be used to coarsely invalidate a whole project's entries.
+    pub project_boundary: Option<String>,
+}
+
+/// Returns the dependencies recorded for `cache_entry_id`.
+pub(crate) async fn get_dependencies(
+    pool: &SqlitePool,
+    cache_entry_id: i64,
+) -> Result<Vec<Dependency>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT path, hash, project_boundary FROM cache_dependencies WHERE cache_entry_id = ?",
+    )
+    .bind(cache_entry_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Dependency {
+            path: row.get("path"),
+            hash: row.get("hash"),
+            project_boundary: row.get("project_boundary"),
+        })
+        .collect())
+}
+
+/// Deletes every cache entry that has a dependency scoped to
+/// `project_boundary`, cascading to their dependencies and store
+/// references. Used to coarsely invalidate an entire project's cached
+/// commands from a single changed file, instead of re-hashing every
+/// dependency of every entry under that project individually.
+pub(crate) async fn delete_cache_entries_by_project_boundary(
+    pool: &SqlitePool,
+    project_boundary: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        DELETE FROM cache_entries
+        WHERE id IN (
+            SELECT DISTINCT cache_entry_id
+            FROM cache_dependencies
+            WHERE project_boundary = ?
+        )
+        "#,
+    )
+    .bind(project_boundary)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records that `cache_entry_id`'s output referenced `store_path`.
+pub(crate) async fn insert_store_reference(
+    pool: &SqlitePool,
+    cache_entry_id: i64,
+    store_path: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO store_path_references (cache_entry_id, store_path) VALUES (?, ?)")
+        .bind(cache_entry_id)
+        .bind(store_path)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the store paths recorded as referenced by `cache_entry_id`'s output.
+pub(crate) async fn get_store_references(
+    pool: &SqlitePool,
+    cache_entry_id: i64,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query("SELECT store_path FROM store_path_references WHERE cache_entry_id = ?")
+        .bind(cache_entry_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|row| row.get("store_path")).collect())
+}
@@ -0,0 +1,57 @@
+use std::io::{ErrorKind, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::command::CommandError;
+
+const GC_SOCKET_PATH: &str = "/nix/var/nix/gc-socket/socket";
+const MAX_CONNECT_ATTEMPTS: u32 = 20;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A held connection to the Nix garbage collector's temp-roots socket.
+///
+/// Registering a store path as a temporary root only lasts as long as the
+/// connection that registered it stays open, so a `GcRoot` must be kept
+/// alive for as long as the cached session depends on the path still being
+/// present in the store.
+pub struct GcRoot {
+    _socket: UnixStream,
+}
+
+impl GcRoot {
+    /// Connects to the Nix GC socket and registers `store_path` as a
+    /// temporary root.
+    pub fn register(store_path: &Path) -> Result<Self, CommandError> {
+        let mut socket = connect_with_retry()?;
+        socket
+            .write_all(store_path.as_os_str().as_encoded_bytes())
+            .map_err(CommandError::GcSocket)?;
+        socket.write_all(b"\n").map_err(CommandError::GcSocket)?;
+
+        Ok(Self { _socket: socket })
+    }
+}
+
+/// Connects to the GC socket, retrying on `ECONNREFUSED` (the collector
+/// process has exited) and `ENOENT` (the collector holds the GC lock but
+/// hasn't recreated the socket yet). Both are transient states during a
+/// collector restart, not real failures, so we close the failed attempt and
+/// loop rather than erroring out immediately.
+fn connect_with_retry() -> Result<UnixStream, CommandError> {
+    let mut attempts = 0;
+    loop {
+        match UnixStream::connect(GC_SOCKET_PATH) {
+            Ok(socket) => return Ok(socket),
+            Err(err)
+                if matches!(err.kind(), ErrorKind::ConnectionRefused | ErrorKind::NotFound)
+                    && attempts < MAX_CONNECT_ATTEMPTS =>
+            {
+                attempts += 1;
+                thread::sleep(RETRY_DELAY);
+            }
+            Err(err) => return Err(CommandError::GcSocket(err)),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod command;
+pub mod db;
+pub mod gc_roots;
+pub mod nix_internal_log;
+pub mod op;
+pub mod project_boundary;
+pub mod store_refs;
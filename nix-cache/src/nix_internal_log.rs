@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A single structured log line emitted by `nix --log-format internal-json`.
+///
+/// Nix writes these as `@nix <json>` on stderr; each line is a self-contained
+/// JSON object tagged by an `action` field.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum NixInternalLog {
+    Start {
+        id: u64,
+        #[serde(default)]
+        level: i32,
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        fields: Vec<Value>,
+    },
+    Stop {
+        id: u64,
+    },
+    Result {
+        id: u64,
+        #[serde(rename = "type")]
+        result_type: i32,
+        #[serde(default)]
+        fields: Vec<Value>,
+    },
+    Msg {
+        level: i32,
+        msg: String,
+        #[serde(default)]
+        raw_msg: Option<String>,
+    },
+}
+
+impl NixInternalLog {
+    /// Parse a single line of `nix --log-format internal-json` output.
+    ///
+    /// Strips the `@nix ` prefix Nix prepends to each structured line. Lines
+    /// that aren't `@nix `-prefixed JSON (e.g. passthrough program output) are
+    /// not structured log messages and yield `None`.
+    pub fn parse_line(line: &str) -> Option<Self> {
+        let json = line.strip_prefix("@nix ")?;
+        serde_json::from_str(json).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_msg() {
+        let line = r#"@nix {"action":"msg","level":1,"msg":"evaluating file '/foo.nix'"}"#;
+        let log = NixInternalLog::parse_line(line);
+        assert_eq!(
+            log,
+            Some(NixInternalLog::Msg {
+                level: 1,
+                msg: "evaluating file '/foo.nix'".to_string(),
+                raw_msg: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_stop() {
+        let line = r#"@nix {"action":"stop","id":42}"#;
+        let log = NixInternalLog::parse_line(line);
+        assert_eq!(log, Some(NixInternalLog::Stop { id: 42 }));
+    }
+
+    #[test]
+    fn test_parse_non_structured_line() {
+        assert_eq!(NixInternalLog::parse_line("plain program output"), None);
+    }
+}
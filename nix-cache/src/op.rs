@@ -1,4 +1,5 @@
 use crate::nix_internal_log::NixInternalLog;
+use crate::project_boundary;
 
 use regex::Regex;
 use std::path::PathBuf;
@@ -14,6 +15,12 @@ pub enum Op {
     ReadFile { source: PathBuf },
     /// Used a tracked devenv string path.
     TrackedPath { source: PathBuf },
+    /// Fetched a tarball, git, or url input into the store.
+    Fetched { uri: String, store_path: PathBuf },
+    /// Downloaded a URI whose resulting store path wasn't reported.
+    Downloaded { uri: String },
+    /// Built a derivation.
+    BuiltDerivation { drv_path: PathBuf },
 }
 
 impl Op {
@@ -28,6 +35,12 @@ impl Op {
                 Regex::new("^trace: devenv readFile: '(?P<source>.*)'$").expect("invalid regex");
             static ref TRACKED_PATH: Regex =
                 Regex::new("^trace: devenv path: '(?P<source>.*)'$").expect("invalid regex");
+            static ref FETCHED: Regex =
+                Regex::new("^fetching '(?P<uri>.*)' -> '(?P<store_path>.*)'$").expect("invalid regex");
+            static ref DOWNLOADED: Regex =
+                Regex::new("^downloading '(?P<uri>.*)'$").expect("invalid regex");
+            static ref BUILT_DERIVATION: Regex =
+                Regex::new("^building '(?P<drv_path>.*)'$").expect("invalid regex");
         }
 
         match log {
@@ -49,6 +62,16 @@ impl Op {
                 } else if let Some(matches) = TRACKED_PATH.captures(msg) {
                     let source = PathBuf::from(&matches["source"]);
                     Some(Op::TrackedPath { source })
+                } else if let Some(matches) = FETCHED.captures(msg) {
+                    let uri = matches["uri"].to_string();
+                    let store_path = PathBuf::from(&matches["store_path"]);
+                    Some(Op::Fetched { uri, store_path })
+                } else if let Some(matches) = DOWNLOADED.captures(msg) {
+                    let uri = matches["uri"].to_string();
+                    Some(Op::Downloaded { uri })
+                } else if let Some(matches) = BUILT_DERIVATION.captures(msg) {
+                    let drv_path = PathBuf::from(&matches["drv_path"]);
+                    Some(Op::BuiltDerivation { drv_path })
                 } else {
                     None
                 }
@@ -56,6 +79,45 @@ impl Op {
             _ => None,
         }
     }
+
+    /// The filesystem path this `Op` depends on, if any.
+    ///
+    /// Used by the cache layer to know which paths to check as cache
+    /// dependencies. `Fetched` and `BuiltDerivation` depend on the store path
+    /// they produced rather than a source file; `Downloaded` reports only a
+    /// URI with no associated path (the fetcher logs a separate `Fetched`
+    /// once the store path is known) and returns `None`.
+    pub fn dependency_path(&self) -> Option<&PathBuf> {
+        match self {
+            Op::CopiedSource { source, .. } => Some(source),
+            Op::EvaluatedFile { source } => Some(source),
+            Op::ReadFile { source } => Some(source),
+            Op::TrackedPath { source } => Some(source),
+            Op::Fetched { store_path, .. } => Some(store_path),
+            Op::BuiltDerivation { drv_path } => Some(drv_path),
+            Op::Downloaded { .. } => None,
+        }
+    }
+
+    /// Whether `dependency_path()` should be checked by re-hashing its
+    /// contents, as opposed to merely checking that it still exists.
+    ///
+    /// Store paths (`Fetched`'s `store_path`, `BuiltDerivation`'s `drv_path`)
+    /// are content-addressed and immutable once created: if the underlying
+    /// fetch or derivation changes, Nix produces a *different* path rather
+    /// than different contents at the same path, so there's nothing to
+    /// re-hash — only whether the path is still present (e.g. hasn't been
+    /// garbage collected) is meaningful.
+    pub fn dependency_is_content_addressed(&self) -> bool {
+        matches!(self, Op::Fetched { .. } | Op::BuiltDerivation { .. })
+    }
+
+    /// The enclosing project boundary of this `Op`'s dependency path, if any.
+    ///
+    /// See [`project_boundary::find_project_boundary`].
+    pub fn project_boundary(&self) -> Option<PathBuf> {
+        project_boundary::find_project_boundary(self.dependency_path()?)
+    }
 }
 
 #[cfg(test)]
@@ -119,6 +181,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fetched() {
+        let log = create_log("fetching 'https://example.com/foo.tar.gz' -> '/nix/store/abc-foo'");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::Fetched {
+                uri: "https://example.com/foo.tar.gz".to_string(),
+                store_path: PathBuf::from("/nix/store/abc-foo"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_downloaded() {
+        let log = create_log("downloading 'https://example.com/foo.tar.gz'");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::Downloaded {
+                uri: "https://example.com/foo.tar.gz".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_built_derivation() {
+        let log = create_log("building '/nix/store/abc-foo.drv'");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::BuiltDerivation {
+                drv_path: PathBuf::from("/nix/store/abc-foo.drv"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_downloaded_has_no_dependency_path() {
+        assert_eq!(
+            Op::Downloaded {
+                uri: "https://example.com/foo".to_string()
+            }
+            .dependency_path(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fetched_dependency_path_is_store_path() {
+        let op = Op::Fetched {
+            uri: "https://example.com/foo.tar.gz".to_string(),
+            store_path: PathBuf::from("/nix/store/abc-foo"),
+        };
+        assert_eq!(
+            op.dependency_path(),
+            Some(&PathBuf::from("/nix/store/abc-foo"))
+        );
+        assert!(op.dependency_is_content_addressed());
+    }
+
+    #[test]
+    fn test_built_derivation_dependency_path_is_drv_path() {
+        let op = Op::BuiltDerivation {
+            drv_path: PathBuf::from("/nix/store/abc-foo.drv"),
+        };
+        assert_eq!(
+            op.dependency_path(),
+            Some(&PathBuf::from("/nix/store/abc-foo.drv"))
+        );
+        assert!(op.dependency_is_content_addressed());
+    }
+
     #[test]
     fn test_unmatched_log() {
         let log = create_log("some unrelated message");
@@ -132,4 +267,12 @@ mod tests {
         let op = Op::from_internal_log(&log);
         assert_eq!(op, None);
     }
+
+    #[test]
+    fn test_dependency_path() {
+        let op = Op::ReadFile {
+            source: PathBuf::from("/path/to/file"),
+        };
+        assert_eq!(op.dependency_path(), Some(&PathBuf::from("/path/to/file")));
+    }
 }
\ No newline at end of file
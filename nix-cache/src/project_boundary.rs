@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+/// Files whose presence in a directory marks it as a project boundary.
+const BOUNDARY_MARKERS: &[&str] = &["devenv.nix", "flake.nix"];
+
+/// Walks upward from `path` looking for the enclosing project boundary: the
+/// nearest ancestor directory containing a `devenv.nix`, `flake.nix`, or
+/// `.git` entry.
+///
+/// The search stops at the filesystem root and at git boundaries exactly
+/// like flake-ref resolution does, returning `None` if no boundary is found.
+pub fn find_project_boundary(path: &Path) -> Option<PathBuf> {
+    let mut dir = if path.is_dir() {
+        Some(path)
+    } else {
+        path.parent()
+    };
+
+    while let Some(current) = dir {
+        let is_boundary = current.join(".git").exists()
+            || BOUNDARY_MARKERS
+                .iter()
+                .any(|marker| current.join(marker).exists());
+
+        if is_boundary {
+            return Some(current.to_path_buf());
+        }
+
+        dir = current.parent();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_finds_flake_nix_boundary() {
+        let dir = tempfile::tempdir().expect("create tempdir");
+        fs::write(dir.path().join("flake.nix"), "").expect("write flake.nix");
+        let nested = dir.path().join("a/b");
+        fs::create_dir_all(&nested).expect("create nested dirs");
+        let file = nested.join("default.nix");
+        fs::write(&file, "").expect("write file");
+
+        assert_eq!(
+            find_project_boundary(&file),
+            Some(dir.path().to_path_buf())
+        );
+    }
+
+    #[test]
+    fn test_finds_git_boundary() {
+        let dir = tempfile::tempdir().expect("create tempdir");
+        fs::create_dir(dir.path().join(".git")).expect("create .git");
+        let file = dir.path().join("default.nix");
+        fs::write(&file, "").expect("write file");
+
+        assert_eq!(
+            find_project_boundary(&file),
+            Some(dir.path().to_path_buf())
+        );
+    }
+
+    #[test]
+    fn test_no_boundary_found() {
+        let dir = tempfile::tempdir().expect("create tempdir");
+        let file = dir.path().join("default.nix");
+        fs::write(&file, "").expect("write file");
+
+        assert_eq!(find_project_boundary(&file), None);
+    }
+}
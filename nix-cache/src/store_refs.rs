@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+
+/// The alphabet nixbase32 encodes store path hashes with (base32, but without
+/// `e`, `o`, `t`, `u` to avoid confusion with other characters and words).
+const NIXBASE32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+/// Store path hashes are always exactly this many nixbase32 characters.
+const HASH_LEN: usize = 32;
+const STORE_PREFIX: &str = "/nix/store/";
+
+/// Scans `bytes` for `/nix/store/<hash>-<name>` references and returns the
+/// deduplicated set of full paths found.
+///
+/// Borrowed from tvix's reference-scanning approach: rather than parsing
+/// structured output, we look for the literal store prefix anywhere in the
+/// bytes and validate what follows it.
+pub fn scan_store_paths(bytes: &[u8]) -> HashSet<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut found = HashSet::new();
+
+    let mut search_from = 0;
+    while let Some(offset) = text[search_from..].find(STORE_PREFIX) {
+        let prefix_start = search_from + offset;
+        let hash_start = prefix_start + STORE_PREFIX.len();
+
+        match parse_store_path(&text, prefix_start, hash_start) {
+            Some(path) => {
+                search_from = prefix_start + path.len();
+                found.insert(path);
+            }
+            None => search_from = hash_start,
+        }
+    }
+
+    found
+}
+
+/// Validates and extracts the `/nix/store/<hash>-<name>` path starting at
+/// `prefix_start`, given that `hash_start` points just past the prefix.
+fn parse_store_path(text: &str, prefix_start: usize, hash_start: usize) -> Option<String> {
+    let bytes = text.as_bytes();
+    if hash_start + HASH_LEN + 1 > bytes.len() {
+        return None;
+    }
+
+    let hash = &bytes[hash_start..hash_start + HASH_LEN];
+    if !hash.iter().all(|b| NIXBASE32_ALPHABET.contains(b)) {
+        return None;
+    }
+
+    if bytes[hash_start + HASH_LEN] != b'-' {
+        return None;
+    }
+
+    let name_start = hash_start + HASH_LEN + 1;
+    let mut name_end = name_start;
+    while name_end < bytes.len() && is_store_name_char(bytes[name_end]) {
+        name_end += 1;
+    }
+
+    if name_end == name_start {
+        return None;
+    }
+
+    Some(text[prefix_start..name_end].to_string())
+}
+
+fn is_store_name_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'+' | b'?' | b'=')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The alphabet itself is exactly `HASH_LEN` (32) characters, so it
+    // doubles as a trivially-valid hash fixture.
+    const VALID_HASH: &str = "0123456789abcdfghijklmnpqrsvwxyz";
+
+    #[test]
+    fn test_finds_single_path() {
+        let paths = scan_store_paths(
+            format!("building '/nix/store/{VALID_HASH}-hello-1.0.drv'").as_bytes(),
+        );
+        assert_eq!(
+            paths,
+            HashSet::from([format!("/nix/store/{VALID_HASH}-hello-1.0.drv")])
+        );
+    }
+
+    #[test]
+    fn test_dedupes_repeated_paths() {
+        let path = format!("/nix/store/{VALID_HASH}-hello-1.0");
+        let bytes = format!("{path} referenced again: {path}");
+        let paths = scan_store_paths(bytes.as_bytes());
+        assert_eq!(paths, HashSet::from([path]));
+    }
+
+    #[test]
+    fn test_rejects_invalid_hash_chars() {
+        // 'e', 'o', 't', 'u' are not in the nixbase32 alphabet; this is the
+        // right length (32) so only the alphabet check can reject it.
+        let invalid_hash = "eout".repeat(8);
+        assert_eq!(invalid_hash.len(), HASH_LEN);
+        let paths = scan_store_paths(format!("/nix/store/{invalid_hash}-hello").as_bytes());
+        assert!(paths.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_missing_separator() {
+        let paths = scan_store_paths(format!("/nix/store/{VALID_HASH}hello").as_bytes());
+        assert!(paths.is_empty());
+    }
+
+    #[test]
+    fn test_no_matches_in_unrelated_text() {
+        assert!(scan_store_paths(b"nothing to see here").is_empty());
+    }
+}